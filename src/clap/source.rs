@@ -38,9 +38,11 @@ use crate::value::{Value, ValueKind};
 
 use crate::ConfigError;
 use clap::parser::ArgMatches;
+use clap::parser::ValueSource;
 use clap::parser::ValuesRef;
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 
 use std::fmt::{Debug, Display, Formatter};
 
@@ -54,6 +56,45 @@ pub struct ClapConfig {
     /// It is optional.  If it's not included, the defaults are
     /// outlined in the documentation.
     pub metadata: Option<HashMap<String, ValueKind>>,
+    /// When true, keys whose value came only from a clap-provided
+    /// default (`ValueSource::DefaultValue`) are skipped by
+    /// `collect`. This lets a `ClapConfig` be added as the top-most
+    /// source without its own defaults clobbering values from
+    /// lower-precedence sources. Defaults to `false` for backwards
+    /// compatibility. Keys listed in `count_keys` are never skipped.
+    pub ignore_defaults: bool,
+    /// When set, `collect` only keeps keys whose `ValueSource` is
+    /// contained in this list, overriding `ignore_defaults`. Keys
+    /// listed in `count_keys` are kept whatever their source. It is
+    /// optional; if it's not included, `ignore_defaults` is used
+    /// instead.
+    pub allowed_value_sources: Option<Vec<ValueSource>>,
+    /// When set, the name of the active subcommand (if any) is
+    /// surfaced as a `ValueKind::String` under this key, in addition
+    /// to its nested config table. It is optional; if it's not
+    /// included, only the nested table is produced.
+    pub command_key: Option<String>,
+    /// When set, each argument id collected is split on this
+    /// separator and built into nested `ValueKind::Table` layers
+    /// (e.g. `database.host` becomes key `host` in table
+    /// `database`), instead of being inserted as a single flat key.
+    /// It is optional; if it's not included, argument ids are
+    /// inserted as flat keys.
+    pub key_separator: Option<String>,
+    /// Keys of args whose `ArgAction` is `Count`. `ArgMatches` has no
+    /// way to recover an arg's `ArgAction`, and a plain
+    /// `.value_parser(value_parser!(u8))` Set/Append arg is stored
+    /// with the same `u8` type id as a `Count` arg, so a key must be
+    /// listed here explicitly to be read via `get_count` instead of
+    /// as a typed `u8` value. It is optional; if it's not included,
+    /// no key is treated as a `Count` arg.
+    ///
+    /// This is keyed by bare arg id and is not scoped per
+    /// subcommand: `child` passes it down unchanged to every nested
+    /// `ClapConfig`, so registering `"port"` here also routes a
+    /// same-named `port` arg inside any subcommand through
+    /// `get_count`, even if it's an ordinary numeric flag there.
+    pub count_keys: Option<HashSet<String>>,
 }
 
 impl ClapConfig {
@@ -63,6 +104,11 @@ impl ClapConfig {
         ClapConfig {
             arg_matches,
             metadata: None,
+            ignore_defaults: false,
+            allowed_value_sources: None,
+            command_key: None,
+            key_separator: None,
+            count_keys: None,
         }
     }
 
@@ -75,9 +121,138 @@ impl ClapConfig {
         ClapConfig {
             arg_matches,
             metadata: Some(metadata),
+            ignore_defaults: false,
+            allowed_value_sources: None,
+            command_key: None,
+            key_separator: None,
+            count_keys: None,
         }
     }
 
+    /// Skip keys during `collect` whose value came only from a
+    /// clap-provided default, rather than the command line or an
+    /// environment variable. Keys registered via `with_count_keys`
+    /// are never skipped.
+    pub fn ignore_defaults(mut self, ignore_defaults: bool) -> ClapConfig {
+        self.ignore_defaults = ignore_defaults;
+        self
+    }
+
+    /// Only collect keys whose `ValueSource` is one of `sources`.
+    /// Takes precedence over `ignore_defaults`. Keys registered via
+    /// `with_count_keys` are collected whatever their source.
+    pub fn with_value_sources(mut self, sources: Vec<ValueSource>) -> ClapConfig {
+        self.allowed_value_sources = Some(sources);
+        self
+    }
+
+    /// Surface the active subcommand's name as a `ValueKind::String`
+    /// under `key`, alongside its nested config table.
+    pub fn with_command_key(mut self, key: impl Into<String>) -> ClapConfig {
+        self.command_key = Some(key.into());
+        self
+    }
+
+    /// Split each collected argument id on `separator` and build
+    /// nested `ValueKind::Table` layers from the parts, instead of
+    /// inserting the id as a single flat key.
+    pub fn with_key_separator(mut self, separator: impl Into<String>) -> ClapConfig {
+        self.key_separator = Some(separator.into());
+        self
+    }
+
+    /// Mark `keys` as args whose `ArgAction` is `Count`, so `collect`
+    /// reads their occurrence count instead of a typed `u8` value.
+    ///
+    /// `keys` is not scoped per subcommand: it applies to every
+    /// nested `ClapConfig` built during subcommand recursion, so a
+    /// key registered here matches a same-named arg inside any
+    /// subcommand too, even if that arg isn't a `Count` arg there.
+    pub fn with_count_keys(mut self, keys: impl IntoIterator<Item = String>) -> ClapConfig {
+        self.count_keys = Some(keys.into_iter().collect());
+        self
+    }
+
+    /// Returns true if `key` was registered via `with_count_keys` as
+    /// an `ArgAction::Count` arg.
+    fn is_count_key(&self, key: &str) -> bool {
+        self.count_keys
+            .as_ref()
+            .is_some_and(|keys| keys.contains(key))
+    }
+
+    /// Build a `ClapConfig` for a nested subcommand's `ArgMatches`,
+    /// inheriting this config's metadata and filtering settings.
+    fn child(&self, arg_matches: ArgMatches) -> ClapConfig {
+        ClapConfig {
+            arg_matches,
+            metadata: self.metadata.clone(),
+            ignore_defaults: self.ignore_defaults,
+            allowed_value_sources: self.allowed_value_sources.clone(),
+            command_key: self.command_key.clone(),
+            key_separator: self.key_separator.clone(),
+            count_keys: self.count_keys.clone(),
+        }
+    }
+
+    /// Insert `value` into `map` at the nested path described by
+    /// `parts`, creating intermediate `ValueKind::Table` layers as
+    /// needed and merging into any table already present for a
+    /// shared prefix (e.g. `database.host` and `database.port`
+    /// populate the same `database` table).
+    fn insert_nested(
+        map: &mut Map<String, Value>,
+        parts: &[&str],
+        value: Value,
+    ) -> crate::error::Result<()> {
+        let (head, rest) = parts
+            .split_first()
+            .expect("a key always has at least one part");
+
+        if rest.is_empty() {
+            if map.contains_key(*head) {
+                return Err(ConfigError::Message(format!(
+                    "Duplicate key '{head}' while building clap config values"
+                )));
+            }
+            map.insert((*head).to_string(), value);
+            return Ok(());
+        }
+
+        let entry = map.entry((*head).to_string()).or_insert_with(|| {
+            Value::new(Some(&String::from("clap")), ValueKind::Table(Map::new()))
+        });
+
+        match &mut entry.kind {
+            ValueKind::Table(table) => Self::insert_nested(table, rest, value),
+            _ => Err(ConfigError::Message(format!(
+                "Key '{head}' is both a value and a table while building clap config values"
+            ))),
+        }
+    }
+
+    /// Returns true if `key` should be skipped by `collect` given the
+    /// configured `ignore_defaults`/`allowed_value_sources` settings.
+    fn should_skip(&self, key: &str) -> bool {
+        // A Count arg's occurrence count is meaningful even at 0, so
+        // it's never filtered out by the default-skipping settings
+        // below, unlike an ordinary arg whose unset default value
+        // would be misleading to report as data.
+        if self.is_count_key(key) {
+            return false;
+        }
+
+        let Some(source) = self.arg_matches.value_source(key) else {
+            return false;
+        };
+
+        if let Some(allowed) = &self.allowed_value_sources {
+            return !allowed.contains(&source);
+        }
+
+        self.ignore_defaults && source == ValueSource::DefaultValue
+    }
+
     /// Get all the command line arguments options as Strings.
     pub fn get_keys(&self) -> Vec<String> {
         self.arg_matches.get_keys()
@@ -133,6 +308,38 @@ impl ClapConfig {
         Ok(Value::new(Some(uri), vk))
     }
 
+    /// Parse a command line argument whose clap-parsed type is `T` into
+    /// a numeric `ValueKind`, widening `T` into `U` (the type the
+    /// `ValueKind` variant actually stores, e.g. `i32` widens into
+    /// `i64`). A single value is stored directly; multiple values are
+    /// collected into a `ValueKind::Array`.
+    fn parse_typed_numeric<T, U>(
+        &self,
+        uri: &str,
+        key: &str,
+        wrap: fn(U) -> ValueKind,
+    ) -> crate::error::Result<Value>
+    where
+        T: Clone + Send + Sync + 'static,
+        U: From<T>,
+    {
+        let values = self
+            .arg_matches
+            .get_many::<T>(key)
+            .expect("Couldn't get numeric argument");
+        let kind = if values.len() == 1 {
+            wrap(U::from(
+                self.arg_matches.get_one::<T>(key).unwrap().clone(),
+            ))
+        } else {
+            let v: Vec<Value> = values
+                .map(|n| Value::new(Some(uri), wrap(U::from(n.clone()))))
+                .collect();
+            ValueKind::Array(v)
+        };
+        Ok(Value::new(Some(uri), kind))
+    }
+
     /// Parse the command line arguments into config values.
     fn parse_arguments(&self, key: &str, type_id: std::any::TypeId) -> crate::error::Result<Value> {
         let uri = String::from("clap");
@@ -158,6 +365,32 @@ impl ClapConfig {
             } else {
                 self.parse_multiple_values_default(&uri, key, values)?
             }
+        } else if type_id == std::any::TypeId::of::<i64>() {
+            self.parse_typed_numeric::<i64, i64>(&uri, key, ValueKind::I64)?
+        } else if type_id == std::any::TypeId::of::<i32>() {
+            self.parse_typed_numeric::<i32, i64>(&uri, key, ValueKind::I64)?
+        } else if type_id == std::any::TypeId::of::<u64>() {
+            self.parse_typed_numeric::<u64, u64>(&uri, key, ValueKind::U64)?
+        } else if type_id == std::any::TypeId::of::<u32>() {
+            self.parse_typed_numeric::<u32, u64>(&uri, key, ValueKind::U64)?
+        } else if type_id == std::any::TypeId::of::<f64>() {
+            self.parse_typed_numeric::<f64, f64>(&uri, key, ValueKind::Float)?
+        } else if type_id == std::any::TypeId::of::<f32>() {
+            self.parse_typed_numeric::<f32, f64>(&uri, key, ValueKind::Float)?
+        } else if type_id == std::any::TypeId::of::<u8>() && self.is_count_key(key) {
+            // clap stores ArgAction::Count occurrences as a u8,
+            // retrievable via get_count. ArgMatches has no way to
+            // tell a Count arg apart from an ordinary u8-typed
+            // Set/Append arg (both store a u8), so only keys
+            // registered via `with_count_keys` take this path. A
+            // count of 0 is a meaningful value, not a missing one,
+            // so it's always emitted.
+            Value::new(
+                Some(&uri),
+                ValueKind::U64(self.arg_matches.get_count(key) as u64),
+            )
+        } else if type_id == std::any::TypeId::of::<u8>() {
+            self.parse_typed_numeric::<u8, u64>(&uri, key, ValueKind::U64)?
         } else {
             Value::new(
                 Some(&uri),
@@ -211,9 +444,38 @@ impl Source for ClapConfig {
         let mut clap_args: Map<String, Value> = Map::new();
 
         for key in self.get_keys() {
+            if self.should_skip(&key) {
+                continue;
+            }
             let value = self.get_item(&key)?;
-            clap_args.insert(key, value);
+            if let Some(separator) = &self.key_separator {
+                let parts: Vec<&str> = key.split(separator.as_str()).collect();
+                Self::insert_nested(&mut clap_args, &parts, value)?;
+            } else {
+                clap_args.insert(key, value);
+            }
         }
+
+        if let Some((name, sub_matches)) = self.arg_matches.subcommand() {
+            let nested = self.child(sub_matches.clone()).collect()?;
+            Self::insert_nested(
+                &mut clap_args,
+                &[name],
+                Value::new(Some(&String::from("clap")), ValueKind::Table(nested)),
+            )?;
+
+            if let Some(command_key) = &self.command_key {
+                Self::insert_nested(
+                    &mut clap_args,
+                    &[command_key.as_str()],
+                    Value::new(
+                        Some(&String::from("clap")),
+                        ValueKind::String(name.to_string()),
+                    ),
+                )?;
+            }
+        }
+
         Ok(clap_args)
     }
 }
@@ -223,6 +485,7 @@ mod tests {
     use crate::{
         clap::source::ClapConfig, config::Config, error::Unexpected, ConfigError, ValueKind,
     };
+    use clap::parser::ValueSource;
     use clap::{Arg, ArgAction, Command};
 
     use std::collections::HashMap;
@@ -365,4 +628,238 @@ mod tests {
         let tagone = vi.next().unwrap().into_string().unwrap();
         assert_eq!(tagone, "tagone");
     }
+
+    /// Test that args declared with clap's typed value parsers produce
+    /// typed config values instead of strings.
+    #[test]
+    fn typed_value_parsers_produce_typed_values() {
+        let m = Command::new("myapp")
+            .arg(
+                Arg::new("count")
+                    .long("count")
+                    .num_args(1)
+                    .value_parser(clap::value_parser!(i64)),
+            )
+            .arg(
+                Arg::new("ratio")
+                    .long("ratio")
+                    .num_args(1)
+                    .value_parser(clap::value_parser!(f64)),
+            )
+            .get_matches_from(vec!["myapp", "--count", "42", "--ratio", "0.5"]);
+
+        let clap_config = ClapConfig::new(m);
+
+        let config = Config::builder().add_source(clap_config).build().unwrap();
+
+        assert_eq!(config.get_int("count").unwrap(), 42);
+        assert_eq!(config.get_float("ratio").unwrap(), 0.5);
+    }
+
+    /// Test that `ignore_defaults` skips keys whose value came only
+    /// from a clap default, so a lower-precedence source can still
+    /// supply the value.
+    #[test]
+    fn ignore_defaults_skips_default_values() {
+        let m = Command::new("myapp")
+            .arg(
+                Arg::new("port")
+                    .long("port")
+                    .num_args(1)
+                    .default_value("8080"),
+            )
+            .arg(Arg::new("host").long("host").num_args(1))
+            .get_matches_from(vec!["myapp", "--host", "example.com"]);
+
+        let clap_config = ClapConfig::new(m).ignore_defaults(true);
+
+        let config = Config::builder().add_source(clap_config).build().unwrap();
+
+        assert_eq!(config.get_string("host").unwrap(), "example.com");
+        assert!(config.get_string("port").is_err());
+    }
+
+    /// Test that `with_value_sources` keeps only keys whose
+    /// `ValueSource` is in the given list, excluding a defaulted key
+    /// even though `ignore_defaults` was never set.
+    #[test]
+    fn with_value_sources_filters_by_source() {
+        let m = Command::new("myapp")
+            .arg(
+                Arg::new("port")
+                    .long("port")
+                    .num_args(1)
+                    .default_value("8080"),
+            )
+            .arg(Arg::new("host").long("host").num_args(1))
+            .get_matches_from(vec!["myapp", "--host", "example.com"]);
+
+        let clap_config = ClapConfig::new(m).with_value_sources(vec![ValueSource::CommandLine]);
+
+        let config = Config::builder().add_source(clap_config).build().unwrap();
+
+        assert_eq!(config.get_string("host").unwrap(), "example.com");
+        assert!(config.get_string("port").is_err());
+    }
+
+    /// Test that a subcommand's arguments are collected into a nested
+    /// table under the subcommand's name, and that the active
+    /// subcommand name is surfaced under a configurable key.
+    #[test]
+    fn subcommand_arguments_are_nested() {
+        let m = Command::new("myapp")
+            .subcommand(
+                Command::new("serve").arg(
+                    Arg::new("port")
+                        .long("port")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(i64)),
+                ),
+            )
+            .get_matches_from(vec!["myapp", "serve", "--port", "8080"]);
+
+        let clap_config = ClapConfig::new(m).with_command_key("command");
+
+        let config = Config::builder().add_source(clap_config).build().unwrap();
+
+        assert_eq!(config.get_int("serve.port").unwrap(), 8080);
+        assert_eq!(config.get_string("command").unwrap(), "serve");
+    }
+
+    /// Test that a top-level arg id colliding with the active
+    /// subcommand's name errors instead of one silently clobbering
+    /// the other, matching the collision handling `insert_nested`
+    /// already does for the `key_separator` case.
+    #[test]
+    fn subcommand_name_collision_with_top_level_arg_errors() {
+        let m = Command::new("myapp")
+            .arg(Arg::new("serve").long("serve").num_args(1))
+            .subcommand(Command::new("serve"))
+            .get_matches_from(vec!["myapp", "--serve", "value", "serve"]);
+
+        let clap_config = ClapConfig::new(m);
+
+        let res = Config::builder().add_source(clap_config).build();
+
+        assert!(res.is_err());
+    }
+
+    /// Test that `ArgAction::Count` args are read as integer
+    /// occurrence counts, and that an unused count still comes
+    /// through as 0 rather than being dropped.
+    #[test]
+    fn count_action_is_integer_occurrences() {
+        let m = Command::new("myapp")
+            .arg(
+                Arg::new("verbose")
+                    .short('v')
+                    .long("verbose")
+                    .action(ArgAction::Count),
+            )
+            .arg(
+                Arg::new("quiet")
+                    .short('q')
+                    .long("quiet")
+                    .action(ArgAction::Count),
+            )
+            .get_matches_from(vec!["myapp", "-vvv"]);
+
+        let clap_config = ClapConfig::new(m)
+            .with_count_keys(vec![String::from("verbose"), String::from("quiet")]);
+
+        let config = Config::builder().add_source(clap_config).build().unwrap();
+
+        assert_eq!(config.get_int("verbose").unwrap(), 3);
+        assert_eq!(config.get_int("quiet").unwrap(), 0);
+    }
+
+    /// Test that an ordinary `u8`-typed Set/Append arg (not
+    /// `ArgAction::Count`) is read via the typed-numeric path rather
+    /// than being hijacked by the `Count`-detection in
+    /// `parse_arguments`, since `ArgMatches` stores both under the
+    /// same `u8` type id and only a key registered via
+    /// `with_count_keys` should take the `get_count` path.
+    #[test]
+    fn plain_u8_arg_is_not_mistaken_for_count() {
+        let m = Command::new("myapp")
+            .arg(
+                Arg::new("retries")
+                    .long("retries")
+                    .num_args(1)
+                    .value_parser(clap::value_parser!(u8)),
+            )
+            .arg(
+                Arg::new("codes")
+                    .long("codes")
+                    .num_args(1..)
+                    .action(ArgAction::Append)
+                    .value_parser(clap::value_parser!(u8)),
+            )
+            .get_matches_from(vec![
+                "myapp", "--retries", "5", "--codes", "1", "--codes", "2", "--codes", "3",
+            ]);
+
+        let clap_config = ClapConfig::new(m);
+
+        let config = Config::builder().add_source(clap_config).build().unwrap();
+
+        assert_eq!(config.get_int("retries").unwrap(), 5);
+        let mut codes = config.get_array("codes").unwrap().into_iter();
+        assert_eq!(codes.next().unwrap().into_int().unwrap(), 1);
+        assert_eq!(codes.next().unwrap().into_int().unwrap(), 2);
+        assert_eq!(codes.next().unwrap().into_int().unwrap(), 3);
+    }
+
+    /// Test that a `Count` arg's 0 occurrence count is still emitted
+    /// when `ignore_defaults` is enabled, since the count itself is
+    /// meaningful even when the arg was never used on the command
+    /// line, unlike an ordinary default value.
+    #[test]
+    fn ignore_defaults_does_not_drop_unused_count_arg() {
+        let m = Command::new("myapp")
+            .arg(
+                Arg::new("quiet")
+                    .short('q')
+                    .long("quiet")
+                    .action(ArgAction::Count),
+            )
+            .get_matches_from(vec!["myapp"]);
+
+        let clap_config = ClapConfig::new(m)
+            .ignore_defaults(true)
+            .with_count_keys(vec![String::from("quiet")]);
+
+        let config = Config::builder().add_source(clap_config).build().unwrap();
+
+        assert_eq!(config.get_int("quiet").unwrap(), 0);
+    }
+
+    /// Test that dotted argument ids are expanded into nested config
+    /// tables when a key separator is configured, merging sibling
+    /// keys into the same parent table.
+    #[test]
+    fn key_separator_expands_dotted_ids_into_tables() {
+        let m = Command::new("myapp")
+            .arg(Arg::new("database.host").long("database.host").num_args(1))
+            .arg(
+                Arg::new("database.port")
+                    .long("database.port")
+                    .num_args(1)
+                    .value_parser(clap::value_parser!(i64)),
+            )
+            .get_matches_from(vec![
+                "myapp",
+                "--database.host",
+                "localhost",
+                "--database.port",
+                "5432",
+            ]);
+
+        let clap_config = ClapConfig::new(m).with_key_separator(".");
+
+        let config = Config::builder().add_source(clap_config).build().unwrap();
+
+        assert_eq!(config.get_string("database.host").unwrap(), "localhost");
+        assert_eq!(config.get_int("database.port").unwrap(), 5432);
+    }
 }